@@ -8,9 +8,17 @@ use bevy_rapier2d::physics::{
 };
 use bevy_rapier2d::rapier::dynamics::{BallJoint, RigidBody, RigidBodyBuilder, RigidBodySet};
 use bevy_rapier2d::rapier::geometry::ColliderBuilder;
-use bevy_rapier2d::rapier::na::Vector2;
+use bevy_rapier2d::rapier::na::{Isometry2, Vector2};
 use nalgebra::Point2;
-use rand::{random, Rng};
+use rand::Rng;
+
+use tetromino::{IVector, TetrominoKind, TetrominoLayout};
+
+#[cfg(feature = "midi")]
+mod midi;
+#[cfg(feature = "netcode")]
+mod netcode;
+mod tetromino;
 
 // fn vain() {
 //     let mut app = App::build();
@@ -32,13 +40,88 @@ fn main() {
         .add_startup_system(setup_board.system())
         .add_startup_system(setup_initial_tetromino.system())
         .add_system(tetromino_movement.system())
-        .add_system(tetromino_sleep_detection.system())
+        .add_system(tetromino_rotation.system())
+        .add_system(tetromino_gravity.system())
+        .add_system(tetromino_lock_delay.system())
+        .add_system(line_clear.system())
         .add_plugin(RapierPhysicsPlugin);
     #[cfg(target_arch = "wasm32")]
     app.add_plugin(bevy_webgl2::WebGL2Plugin);
+    #[cfg(feature = "midi")]
+    app.add_startup_system(midi::setup_midi.system())
+        .add_system(midi::midi_input.system())
+        .add_system(midi::midi_render.system());
+    #[cfg(feature = "netcode")]
+    app.add_startup_system(setup_versus_match.system())
+        .add_system(versus_match_step.system());
     app.run();
 }
 
+/// startup system (only added when the `netcode` feature is enabled)
+#[cfg(feature = "netcode")]
+fn setup_versus_match(commands: &mut Commands) {
+    let seed: u64 = rand::thread_rng().gen();
+    // `check_distance` is how many frames the synctest session rolls back
+    // and resimulates each frame, comparing checksums to confirm the
+    // simulation reproduces the same states.
+    commands.insert_resource(netcode::VersusMatch::new(seed.max(1), 2));
+}
+
+/// system (only added when the `netcode` feature is enabled)
+///
+/// Hot-seat input for the local versus match: player one on the arrow keys
+/// and space, player two on WASD and left shift. A real networked session
+/// would read player two's input from the wire instead; see the module docs
+/// on `netcode`.
+#[cfg(feature = "netcode")]
+fn versus_match_step(input: Res<Input<KeyCode>>, mut versus: ResMut<netcode::VersusMatch>) {
+    let player_one = local_versus_input(
+        &input,
+        KeyCode::Left,
+        KeyCode::Right,
+        KeyCode::Up,
+        KeyCode::Down,
+        KeyCode::Space,
+    );
+    let player_two = local_versus_input(
+        &input,
+        KeyCode::A,
+        KeyCode::D,
+        KeyCode::W,
+        KeyCode::S,
+        KeyCode::LShift,
+    );
+    versus.advance([player_one, player_two]);
+}
+
+#[cfg(feature = "netcode")]
+fn local_versus_input(
+    input: &Input<KeyCode>,
+    left: KeyCode,
+    right: KeyCode,
+    rotate: KeyCode,
+    soft_drop: KeyCode,
+    hard_drop: KeyCode,
+) -> netcode::PlayerInput {
+    let mut bits = 0u8;
+    if input.pressed(left) {
+        bits |= netcode::PlayerInput::LEFT;
+    }
+    if input.pressed(right) {
+        bits |= netcode::PlayerInput::RIGHT;
+    }
+    if input.just_pressed(rotate) {
+        bits |= netcode::PlayerInput::ROTATE;
+    }
+    if input.pressed(soft_drop) {
+        bits |= netcode::PlayerInput::SOFT_DROP;
+    }
+    if input.just_pressed(hard_drop) {
+        bits |= netcode::PlayerInput::HARD_DROP;
+    }
+    netcode::PlayerInput::from_bits(bits)
+}
+
 //
 // Note on coordinate systems used
 // The game uses different coordinate systems.
@@ -82,20 +165,69 @@ const BLOCK_LINEAR_DAMPING: f32 = 1.0;
 const MOVEMENT_FORCE: f32 = 20.0;
 const TORQUE: f32 = 20.0;
 
+/// Downward impulse applied to the current piece on each gravity tick.
+const GRAVITY_TICK_IMPULSE: f32 = 1.2;
+/// Gravity interval (in fixed ticks) at level 1.
+const BASE_GRAVITY_INTERVAL_TICKS: u64 = 48;
+/// The gravity interval shortens by this many ticks per level.
+const GRAVITY_INTERVAL_STEP_TICKS: u64 = 4;
+/// Gravity never ticks faster than this, however high the level.
+const MIN_GRAVITY_INTERVAL_TICKS: u64 = 4;
+/// Soft drop divides the current gravity interval by this factor.
+const SOFT_DROP_DIVISOR: u64 = 4;
+
+/// Fixed ticks to wait, once a piece has touched down, before it locks in place.
+const LOCK_DELAY_TICKS: u64 = 30;
+/// "Infinity" lock delay: a piece can have its lock countdown reset by player
+/// input this many times before it is forced to lock regardless.
+const MAX_LOCK_RESETS: u32 = 15;
+
+/// Points awarded for clearing 1, 2, 3 or 4 lines at once, before the
+/// level multiplier.
+const LINE_CLEAR_SCORES: [u32; 4] = [100, 300, 500, 800];
+/// A level up is awarded for every this many lines cleared.
+const LINES_PER_LEVEL: u32 = 10;
+
 const N_LANES: usize = 10;
 const N_ROWS: usize = 20;
 
-/// Type for our discrete coordinate systems
-/// (column, row) or (x, y)
-type IVector = (i32, i32);
-
 /// This struct is used as a Bevy resource: Res<Game>
-struct Game {
+pub(crate) struct Game {
     n_lanes: usize,
     n_rows: usize,
     block_color: Option<Handle<ColorMaterial>>,
-    current_tetromino_blocks: HashSet<Entity>,
+    pub(crate) current_tetromino_blocks: Vec<Entity>,
     current_tetromino_joints: Vec<Entity>,
+    /// The kind of the tetromino currently in play, used to look up the right
+    /// wall-kick table when rotating.
+    current_tetromino_kind: Option<TetrominoKind>,
+    /// Tetromino-coordinate layout of the current tetromino, kept in sync with
+    /// whatever orientation it has actually been rotated to.
+    current_tetromino_coords: [IVector; 4],
+    /// 0 = spawn, 1 = R, 2 = 2, 3 = L, following SRS naming.
+    current_tetromino_rotation: u8,
+    /// Fixed-tick counter, advanced once per lock-delay system run.
+    tick: u64,
+    /// The tick at which the current piece will lock, if it is still touching
+    /// down at that point. `None` means the piece is not currently touching down.
+    next_lock_tick: Option<u64>,
+    /// The next tick at which gravity should give the current piece a
+    /// downward impulse.
+    next_gravity_tick: u64,
+    /// How many times the current piece's lock countdown has been reset by
+    /// player input. Capped at `MAX_LOCK_RESETS`.
+    lock_resets: u32,
+    /// Set once a piece locks; cleared once a subsequent line-clear scan finds
+    /// no more completed rows. Kept set across clears so blocks dislodged by
+    /// one clear get a chance to resettle and be scanned again.
+    pending_line_clear_check: bool,
+    /// Lowest board row the current tetromino occupied the last time soft
+    /// drop was checked, so we can award points per row actually descended
+    /// rather than per tick. `None` whenever Down isn't held.
+    soft_drop_reference_row: Option<i32>,
+    score: u32,
+    level: u32,
+    lines_cleared: u32,
     camera: Option<Entity>,
 }
 
@@ -133,6 +265,33 @@ impl Game {
 
         (x, y)
     }
+
+    ///
+    /// Translate from physics coordinates back to board coordinates, rounding
+    /// to the nearest lane/row. This is the inverse of `board_to_physics`.
+    ///
+    pub(crate) fn physics_to_board(&self, (x, y): (f32, f32)) -> IVector {
+        let col = (x - self.left_edge_x() - 0.5).round() as i32;
+        let row = (y - self.floor_y() - 0.5).round() as i32;
+
+        (col, row)
+    }
+
+    ///
+    /// Push the current piece's lock tick back out, as long as it hasn't
+    /// already used up its allowance of resets. No-op if the piece isn't
+    /// currently counting down to a lock.
+    ///
+    fn reset_lock_delay(&mut self) {
+        if self.next_lock_tick.is_none() {
+            return;
+        }
+        if self.lock_resets >= MAX_LOCK_RESETS {
+            return;
+        }
+        self.next_lock_tick = Some(self.tick + LOCK_DELAY_TICKS);
+        self.lock_resets += 1;
+    }
 }
 
 impl Default for Game {
@@ -141,8 +300,20 @@ impl Default for Game {
             n_lanes: N_LANES,
             n_rows: N_ROWS,
             block_color: None,
-            current_tetromino_blocks: HashSet::new(),
+            current_tetromino_blocks: vec![],
             current_tetromino_joints: vec![],
+            current_tetromino_kind: None,
+            current_tetromino_coords: [(0, 0); 4],
+            current_tetromino_rotation: 0,
+            tick: 0,
+            next_lock_tick: None,
+            next_gravity_tick: 0,
+            lock_resets: 0,
+            pending_line_clear_check: false,
+            soft_drop_reference_row: None,
+            score: 0,
+            level: 1,
+            lines_cleared: 0,
             camera: None,
         }
     }
@@ -164,75 +335,7 @@ fn setup_game(
     game.camera = commands.spawn(Camera2dBundle::default()).current_entity();
 }
 
-/// Represent Tetris' different tetromino kinds
-#[derive(Clone, Copy, Debug)]
-enum TetrominoKind {
-    I,
-    O,
-    T,
-    J,
-    L,
-    S,
-    Z,
-}
-
-impl TetrominoKind {
-    fn random() -> Self {
-        match rand::thread_rng().gen_range(0..=6) {
-            0 => Self::I,
-            1 => Self::O,
-            2 => Self::T,
-            3 => Self::J,
-            4 => Self::L,
-            5 => Self::S,
-            _ => Self::Z,
-        }
-    }
-
-    fn layout(&self) -> TetrominoLayout {
-        match self {
-            Self::I => TetrominoLayout {
-                coords: [(1, 1), (1, 0), (1, -1), (1, -2)],
-                joints: vec![(0, 1), (1, 2), (2, 3)],
-            },
-            Self::O => TetrominoLayout {
-                coords: [(0, 0), (1, 0), (1, -1), (0, -1)],
-                joints: vec![(0, 1), (1, 2), (2, 3), (1, 0)],
-            },
-            Self::T => TetrominoLayout {
-                coords: [(0, 0), (1, 0), (2, 0), (1, -1)],
-                joints: vec![(0, 1), (1, 2), (1, 3)],
-            },
-            Self::J => TetrominoLayout {
-                coords: [(1, 0), (1, -1), (1, -2), (0, -2)],
-                joints: vec![(0, 1), (1, 2), (2, 3)],
-            },
-            Self::L => TetrominoLayout {
-                coords: [(1, 0), (1, -1), (1, -2), (2, -2)],
-                joints: vec![(0, 1), (1, 2), (2, 3)],
-            },
-            Self::S => TetrominoLayout {
-                coords: [(0, -1), (1, -1), (1, 0), (2, 0)],
-                joints: vec![(0, 1), (1, 2), (2, 3)],
-            },
-            Self::Z => TetrominoLayout {
-                coords: [(0, 0), (1, 0), (1, -1), (2, -1)],
-                joints: vec![(0, 1), (1, 2), (2, 3)],
-            },
-        }
-    }
-}
-
-/// The layout of one tetromino
-struct TetrominoLayout {
-    /// All tetrominos consist of 4 blocks, so we use a fixed-size array.
-    /// This is expressed in the tetromino coordinate system
-    coords: [IVector; 4],
-    /// OTOH, The number of _joints_ is variable..
-    joints: Vec<(usize, usize)>,
-}
-
-struct Block;
+pub(crate) struct Block;
 
 // startup system
 fn setup_board(
@@ -272,10 +375,28 @@ fn spawn_tetromino(commands: &mut Commands, game: &mut Game) {
 
     let block_entities: Vec<Entity> = coords
         .iter()
-        .map(|_| spawn_block(commands, game, kind, coords[0]))
+        .map(|coord| spawn_block(commands, game, kind, *coord))
         .collect();
 
-    let joint_entities: Vec<Entity> = joints
+    game.current_tetromino_joints = build_joints(commands, &block_entities, &coords, &joints);
+    game.current_tetromino_blocks = block_entities;
+    game.current_tetromino_kind = Some(kind);
+    game.current_tetromino_coords = coords;
+    game.current_tetromino_rotation = 0;
+}
+
+///
+/// Connect each pair of blocks named in `joints` with a ball joint, anchored
+/// so the joint exerts no force while the blocks sit at their `coords`
+/// positions relative to one another.
+///
+fn build_joints(
+    commands: &mut Commands,
+    blocks: &[Entity],
+    coords: &[IVector; 4],
+    joints: &[(usize, usize)],
+) -> Vec<Entity> {
+    joints
         .iter()
         .map(|(i, j)| {
             let x_dir = coords[*j].0 as f32 - coords[*i].0 as f32;
@@ -287,16 +408,54 @@ fn spawn_tetromino(commands: &mut Commands, game: &mut Game) {
             commands
                 .spawn((JointBuilderComponent::new(
                     BallJoint::new(anchor_1, anchor_2),
-                    block_entities[*i],
-                    block_entities[*j],
+                    blocks[*i],
+                    blocks[*j],
                 ),))
                 .current_entity()
                 .unwrap()
         })
-        .collect();
+        .collect()
+}
 
-    game.current_tetromino_blocks = block_entities.into_iter().collect();
-    game.current_tetromino_joints = joint_entities.into_iter().collect();
+///
+/// Rotate every tetromino-coordinate block 90 degrees clockwise about the
+/// tetromino's pivot block, `coords[1]`: (x, y) -> (y, -x) relative to it.
+///
+fn rotate_clockwise(coords: &[IVector; 4]) -> [IVector; 4] {
+    let (pivot_x, pivot_y) = coords[1];
+    let mut rotated = [(0, 0); 4];
+    for (i, (x, y)) in coords.iter().enumerate() {
+        rotated[i] = (pivot_x + (*y - pivot_y), pivot_y - (*x - pivot_x));
+    }
+    rotated
+}
+
+///
+/// SRS wall-kick offsets to try, in order, for a clockwise rotation starting
+/// from `from_rotation` (0 = spawn, 1 = R, 2 = 2, 3 = L).
+///
+fn wall_kicks(kind: TetrominoKind, from_rotation: u8) -> [IVector; 5] {
+    const JLSTZ_KICKS: [[IVector; 5]; 4] = [
+        [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+        [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+        [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+        [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+    ];
+    const I_KICKS: [[IVector; 5]; 4] = [
+        [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+        [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+        [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+        [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+    ];
+    const NO_KICKS: [IVector; 5] = [(0, 0), (0, 0), (0, 0), (0, 0), (0, 0)];
+
+    let table = match kind {
+        TetrominoKind::O => return NO_KICKS,
+        TetrominoKind::I => I_KICKS,
+        _ => JLSTZ_KICKS,
+    };
+
+    table[from_rotation as usize]
 }
 
 fn spawn_block(
@@ -331,7 +490,7 @@ fn spawn_block(
 // system
 fn tetromino_movement(
     input: Res<Input<KeyCode>>,
-    game: Res<Game>,
+    mut game: ResMut<Game>,
     rigid_body_query: Query<&RigidBodyHandleComponent>,
     mut rigid_bodies: ResMut<RigidBodySet>,
 ) {
@@ -347,17 +506,127 @@ fn tetromino_movement(
             }
         }
     }
+
+    if input.just_pressed(KeyCode::Right) || input.just_pressed(KeyCode::Left) {
+        game.reset_lock_delay();
+    }
+}
+
+// system
+fn tetromino_rotation(
+    commands: &mut Commands,
+    input: Res<Input<KeyCode>>,
+    mut game: ResMut<Game>,
+    block_query: Query<(Entity, &Block, &RigidBodyHandleComponent)>,
+    mut rigid_bodies: ResMut<RigidBodySet>,
+) {
+    if !(input.just_pressed(KeyCode::Up) || input.just_pressed(KeyCode::X)) {
+        return;
+    }
+
+    let kind = match game.current_tetromino_kind {
+        Some(kind) => kind,
+        None => return,
+    };
+
+    // The O piece has no block at its centre, so rotating it about its
+    // pivot block in `rotate_clockwise` would visibly translate it despite
+    // `wall_kicks` returning an all-zero table for it. An O never needs to
+    // rotate in the first place, so just skip it entirely.
+    if let TetrominoKind::O = kind {
+        return;
+    }
+
+    let board_position_of = |entity: Entity, rigid_bodies: &RigidBodySet| -> Option<IVector> {
+        let (_, _, handle) = block_query.get(entity).ok()?;
+        let body = rigid_bodies.get(handle.handle())?;
+        let translation = body.position().translation;
+        Some(game.physics_to_board((translation.x, translation.y)))
+    };
+
+    // The board position that the tetromino's local (0, 0) currently maps to,
+    // derived from the first block's actual (possibly drifted) position.
+    let anchor = match board_position_of(game.current_tetromino_blocks[0], &rigid_bodies) {
+        Some((x, y)) => {
+            let (local_x, local_y) = game.current_tetromino_coords[0];
+            (x - local_x, y - local_y)
+        }
+        None => return,
+    };
+
+    let occupied: HashSet<IVector> = block_query
+        .iter()
+        .filter(|(entity, _, _)| !game.current_tetromino_blocks.contains(entity))
+        .filter_map(|(entity, _, _)| board_position_of(entity, &rigid_bodies))
+        .collect();
+
+    let rotated_coords = rotate_clockwise(&game.current_tetromino_coords);
+
+    for (dx, dy) in wall_kicks(kind, game.current_tetromino_rotation).iter() {
+        let candidate_positions: Vec<IVector> = rotated_coords
+            .iter()
+            .map(|(x, y)| (anchor.0 + x + dx, anchor.1 + y + dy))
+            .collect();
+
+        let fits = candidate_positions.iter().all(|(col, row)| {
+            *col >= 0
+                && (*col as usize) < game.n_lanes
+                && *row >= 0
+                && !occupied.contains(&(*col, *row))
+        });
+
+        if !fits {
+            continue;
+        }
+
+        for (entity, board_coord) in game
+            .current_tetromino_blocks
+            .clone()
+            .iter()
+            .zip(candidate_positions.iter())
+        {
+            if let Ok((_, _, handle)) = block_query.get(*entity) {
+                if let Some(body) = rigid_bodies.get_mut(handle.handle()) {
+                    let (x, y) = game.board_to_physics(*board_coord);
+                    body.set_position(Isometry2::translation(x, y), true);
+                    body.set_linvel(Vector2::new(0.0, 0.0), true);
+                    body.set_angvel(0.0, true);
+                }
+            }
+        }
+
+        for joint in game.current_tetromino_joints.drain(..).collect::<Vec<_>>() {
+            commands.despawn(joint);
+        }
+        let blocks = game.current_tetromino_blocks.clone();
+        game.current_tetromino_joints =
+            build_joints(commands, &blocks, &rotated_coords, &kind.layout().joints);
+        game.current_tetromino_coords = rotated_coords;
+        game.current_tetromino_rotation = (game.current_tetromino_rotation + 1) % 4;
+        game.reset_lock_delay();
+        break;
+    }
 }
 
 // system
-fn tetromino_sleep_detection(
+///
+/// Rather than fusing the piece the instant it stops moving, wait out a lock
+/// delay once it first touches down, so last-moment adjustments are still
+/// possible. The countdown is reset by player input (see `reset_lock_delay`)
+/// up to `MAX_LOCK_RESETS` times, after which the piece is forced to lock.
+///
+fn tetromino_lock_delay(
     commands: &mut Commands,
     mut game: ResMut<Game>,
     block_query: Query<(Entity, &RigidBodyHandleComponent)>,
     rigid_bodies: ResMut<RigidBodySet>,
     mut materials: ResMut<Assets<ColorMaterial>>,
 ) {
-    let all_blocks_sleeping = game.current_tetromino_blocks.iter().all(|block_entity| {
+    game.tick += 1;
+
+    // A block resting on the floor or on a settled block reports as sleeping;
+    // that is our signal that the piece has touched down.
+    let touching_down = game.current_tetromino_blocks.iter().all(|block_entity| {
         block_query
             .get(*block_entity)
             .ok()
@@ -366,14 +635,276 @@ fn tetromino_sleep_detection(
             .unwrap_or(false)
     });
 
-    if all_blocks_sleeping {
-        for joint in &game.current_tetromino_joints {
-            commands.despawn(*joint);
+    if !touching_down {
+        game.next_lock_tick = None;
+        game.lock_resets = 0;
+        return;
+    }
+
+    let lock_tick = *game
+        .next_lock_tick
+        .get_or_insert(game.tick + LOCK_DELAY_TICKS);
+
+    if game.tick < lock_tick {
+        return;
+    }
+
+    lock_piece(commands, &mut game, &mut materials);
+}
+
+///
+/// Commit the current piece: tear down its joints, hand it over to the
+/// line-clear scan, and spawn the next one.
+///
+fn lock_piece(commands: &mut Commands, game: &mut Game, materials: &mut Assets<ColorMaterial>) {
+    for joint in &game.current_tetromino_joints {
+        commands.despawn(*joint);
+    }
+
+    game.block_color = Some(materials.add(random_color().into()));
+    game.next_lock_tick = None;
+    game.lock_resets = 0;
+    game.pending_line_clear_check = true;
+    spawn_tetromino(commands, game);
+}
+
+fn gravity_interval_ticks(level: u32) -> u64 {
+    let steps = level.saturating_sub(1) as u64;
+    BASE_GRAVITY_INTERVAL_TICKS
+        .saturating_sub(steps * GRAVITY_INTERVAL_STEP_TICKS)
+        .max(MIN_GRAVITY_INTERVAL_TICKS)
+}
+
+fn soft_drop_interval_ticks(level: u32) -> u64 {
+    (gravity_interval_ticks(level) / SOFT_DROP_DIVISOR).max(1)
+}
+
+// system
+///
+/// Pull the current piece down on a cadence derived from the level, or much
+/// faster while Down is held. Space instead slams the piece straight to its
+/// resting position and locks it immediately.
+///
+fn tetromino_gravity(
+    commands: &mut Commands,
+    input: Res<Input<KeyCode>>,
+    mut game: ResMut<Game>,
+    block_query: Query<(Entity, &Block, &RigidBodyHandleComponent)>,
+    mut rigid_bodies: ResMut<RigidBodySet>,
+    mut materials: ResMut<Assets<ColorMaterial>>,
+) {
+    if game.current_tetromino_blocks.is_empty() {
+        game.soft_drop_reference_row = None;
+        return;
+    }
+
+    if input.just_pressed(KeyCode::Space) {
+        hard_drop(
+            commands,
+            &mut game,
+            &block_query,
+            &mut rigid_bodies,
+            &mut materials,
+        );
+        game.soft_drop_reference_row = None;
+        return;
+    }
+
+    let soft_dropping = input.pressed(KeyCode::Down);
+
+    // Award soft-drop points per row actually descended, not per tick: the
+    // piece is nudged by a physics impulse rather than snapped a full row at
+    // a time, so ticks and rows don't correspond 1:1.
+    let current_row = lowest_tetromino_row(&game, &block_query, &rigid_bodies);
+    if soft_dropping {
+        if let (Some(row), Some(previous_row)) = (current_row, game.soft_drop_reference_row) {
+            if row < previous_row {
+                game.score += (previous_row - row) as u32;
+            }
+        }
+        game.soft_drop_reference_row = current_row;
+    } else {
+        game.soft_drop_reference_row = None;
+    }
+
+    let interval = if soft_dropping {
+        soft_drop_interval_ticks(game.level)
+    } else {
+        gravity_interval_ticks(game.level)
+    };
+
+    if game.tick < game.next_gravity_tick {
+        return;
+    }
+    game.next_gravity_tick = game.tick + interval;
+
+    if game.next_lock_tick.is_some() {
+        // The piece is already touching down and counting toward a lock;
+        // don't wake it back up with a no-op downward impulse every tick,
+        // or its lock countdown (and `lock_resets` cap) can never elapse.
+        return;
+    }
+
+    for entity in game.current_tetromino_blocks.clone() {
+        if let Ok((_, _, handle)) = block_query.get(entity) {
+            if let Some(body) = rigid_bodies.get_mut(handle.handle()) {
+                body.apply_impulse(Vector2::new(0.0, -GRAVITY_TICK_IMPULSE), true);
+            }
         }
+    }
+}
+
+/// The lowest board row any of the current tetromino's blocks occupies.
+fn lowest_tetromino_row(
+    game: &Game,
+    block_query: &Query<(Entity, &Block, &RigidBodyHandleComponent)>,
+    rigid_bodies: &RigidBodySet,
+) -> Option<i32> {
+    game.current_tetromino_blocks
+        .iter()
+        .filter_map(|entity| {
+            let (_, _, handle) = block_query.get(*entity).ok()?;
+            let body = rigid_bodies.get(handle.handle())?;
+            let translation = body.position().translation;
+            Some(game.physics_to_board((translation.x, translation.y)).1)
+        })
+        .min()
+}
 
-        game.block_color = Some(materials.add(random_color().into()));
-        spawn_tetromino(commands, &mut game);
+///
+/// Drop the current piece straight down to the lowest row it can occupy
+/// without overlapping the floor or a settled block, awarding 2 points per
+/// row descended, then lock it immediately.
+///
+fn hard_drop(
+    commands: &mut Commands,
+    game: &mut Game,
+    block_query: &Query<(Entity, &Block, &RigidBodyHandleComponent)>,
+    rigid_bodies: &mut RigidBodySet,
+    materials: &mut Assets<ColorMaterial>,
+) {
+    let board_position_of = |entity: Entity, rigid_bodies: &RigidBodySet| -> Option<IVector> {
+        let (_, _, handle) = block_query.get(entity).ok()?;
+        let body = rigid_bodies.get(handle.handle())?;
+        let translation = body.position().translation;
+        Some(game.physics_to_board((translation.x, translation.y)))
+    };
+
+    let current_positions: Vec<(Entity, IVector)> = game
+        .current_tetromino_blocks
+        .iter()
+        .filter_map(|entity| board_position_of(*entity, rigid_bodies).map(|pos| (*entity, pos)))
+        .collect();
+
+    let occupied: HashSet<IVector> = block_query
+        .iter()
+        .filter(|(entity, _, _)| !game.current_tetromino_blocks.contains(entity))
+        .filter_map(|(entity, _, _)| board_position_of(entity, rigid_bodies))
+        .collect();
+
+    let mut drop = 0i32;
+    loop {
+        let next_drop = drop + 1;
+        let blocked = current_positions.iter().any(|(_, (col, row))| {
+            let new_row = row - next_drop;
+            new_row < 0 || occupied.contains(&(*col, new_row))
+        });
+        if blocked {
+            break;
+        }
+        drop = next_drop;
     }
+
+    for (entity, (col, row)) in &current_positions {
+        if let Ok((_, _, handle)) = block_query.get(*entity) {
+            if let Some(body) = rigid_bodies.get_mut(handle.handle()) {
+                let (x, y) = game.board_to_physics((*col, row - drop));
+                body.set_position(Isometry2::translation(x, y), true);
+                body.set_linvel(Vector2::new(0.0, 0.0), true);
+                body.set_angvel(0.0, true);
+            }
+        }
+    }
+
+    game.score += 2 * drop as u32;
+    lock_piece(commands, game, materials);
+}
+
+// system
+///
+/// Once a piece has locked, scan the settled blocks for completed rows and
+/// clear them. Blocks above a cleared row are left to loose Rapier gravity to
+/// resettle, rather than being shifted down ourselves, so this stays pending
+/// until a scan finds nothing left to clear.
+///
+fn line_clear(
+    commands: &mut Commands,
+    mut game: ResMut<Game>,
+    block_query: Query<(Entity, &Block, &RigidBodyHandleComponent)>,
+    rigid_bodies: ResMut<RigidBodySet>,
+) {
+    if !game.pending_line_clear_check {
+        return;
+    }
+
+    let settled_blocks = || {
+        block_query
+            .iter()
+            .filter(|(entity, _, _)| !game.current_tetromino_blocks.contains(entity))
+    };
+
+    let all_settled_asleep = settled_blocks().all(|(_, _, handle)| {
+        rigid_bodies
+            .get(handle.handle())
+            .map(RigidBody::is_sleeping)
+            .unwrap_or(true)
+    });
+
+    if !all_settled_asleep {
+        return;
+    }
+
+    let settled_board_positions: Vec<(Entity, IVector)> = settled_blocks()
+        .filter_map(|(entity, _, handle)| {
+            let body = rigid_bodies.get(handle.handle())?;
+            let translation = body.position().translation;
+            Some((
+                entity,
+                game.physics_to_board((translation.x, translation.y)),
+            ))
+        })
+        .collect();
+
+    let mut lane_counts = vec![0usize; game.n_rows];
+    for (_, (_, row)) in &settled_board_positions {
+        if let Some(count) = lane_counts.get_mut(*row as usize) {
+            *count += 1;
+        }
+    }
+
+    let full_rows: Vec<i32> = (0..game.n_rows as i32)
+        .filter(|row| lane_counts[*row as usize] >= game.n_lanes)
+        .collect();
+
+    if full_rows.is_empty() {
+        game.pending_line_clear_check = false;
+        return;
+    }
+
+    for (entity, (_, row)) in &settled_board_positions {
+        if full_rows.contains(row) {
+            commands.despawn(*entity);
+        }
+    }
+
+    let lines_cleared_now = full_rows.len().min(LINE_CLEAR_SCORES.len()) as u32;
+    game.score += LINE_CLEAR_SCORES[lines_cleared_now as usize - 1] * game.level;
+    game.lines_cleared += full_rows.len() as u32;
+    game.level = 1 + game.lines_cleared / LINES_PER_LEVEL;
+
+    // Clearing a row drops the blocks above it; keep checking until a scan
+    // comes back clean.
+    game.pending_line_clear_check = true;
 }
 
 fn random_color() -> Color {
@@ -382,3 +913,58 @@ fn random_color() -> Color {
     let b = rand::thread_rng().gen_range(0..255);
     byte_rgb(r, g, b)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rotate_clockwise_four_times_returns_to_start() {
+        let coords = TetrominoKind::T.layout().coords;
+        let mut rotated = coords;
+        for _ in 0..4 {
+            rotated = rotate_clockwise(&rotated);
+        }
+        assert_eq!(rotated, coords);
+    }
+
+    #[test]
+    fn wall_kicks_first_candidate_is_always_the_identity() {
+        for kind in [
+            TetrominoKind::I,
+            TetrominoKind::O,
+            TetrominoKind::T,
+            TetrominoKind::J,
+            TetrominoKind::L,
+            TetrominoKind::S,
+            TetrominoKind::Z,
+        ] {
+            for rotation in 0..4u8 {
+                assert_eq!(wall_kicks(kind, rotation)[0], (0, 0));
+            }
+        }
+    }
+
+    #[test]
+    fn wall_kicks_o_piece_never_offers_a_real_kick() {
+        for rotation in 0..4u8 {
+            assert_eq!(wall_kicks(TetrominoKind::O, rotation), [(0, 0); 5]);
+        }
+    }
+
+    #[test]
+    fn jlstz_kicks_keep_a_piece_against_the_left_wall_in_bounds() {
+        // A J piece anchored at the leftmost column, as it would be right
+        // after being shifted all the way over before rotating.
+        let anchor: IVector = (0, 5);
+        let rotated = rotate_clockwise(&TetrominoKind::J.layout().coords);
+
+        let has_in_bounds_kick = wall_kicks(TetrominoKind::J, 0).iter().any(|(dx, dy)| {
+            rotated
+                .iter()
+                .all(|(x, y)| anchor.0 + x + dx >= 0 && (anchor.0 + x + dx) < N_LANES as i32)
+        });
+
+        assert!(has_in_bounds_kick);
+    }
+}
@@ -0,0 +1,276 @@
+//! Optional input/output backend that drives the game from a Novation
+//! Launchpad-style MIDI grid controller, in addition to the keyboard.
+//!
+//! This whole module is gated behind the `midi` cargo feature so the
+//! default Bevy/WASM build never links against `midir`.
+
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+
+use bevy::app::AppExit;
+use bevy::prelude::*;
+use bevy_rapier2d::physics::RigidBodyHandleComponent;
+use bevy_rapier2d::rapier::dynamics::RigidBodySet;
+use midir::{Ignore, MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
+
+use crate::{Block, Game, IVector};
+
+/// A single cell on the grid controller, addressed the same way as the
+/// board: (column, row), origin at the bottom-left pad.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Pad {
+    pub x: u8,
+    pub y: u8,
+}
+
+impl Pad {
+    /// Launchpad-style note numbering: each row spans one decade of note
+    /// numbers, e.g. row 0 is notes 11-18, row 1 is notes 21-28, and so on.
+    fn to_note(self) -> u8 {
+        (self.y + 1) * 10 + (self.x + 1)
+    }
+
+    /// Inverse of `to_note`. `None` for any note that isn't one `to_note`
+    /// could have produced (scene/automap buttons, stray messages, ...).
+    fn from_note(note: u8) -> Option<Self> {
+        let decade = note / 10;
+        let ones = note % 10;
+        if ones == 0 || !(1..=9).contains(&decade) {
+            return None;
+        }
+        Some(Pad {
+            x: ones - 1,
+            y: decade - 1,
+        })
+    }
+}
+
+/// High-level inputs the grid controller can produce. These feed into the
+/// same movement/rotation paths the keyboard uses.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ControlEvent {
+    MoveLeft,
+    MoveRight,
+    Rotate,
+    SoftDrop,
+    HardDrop,
+    SpeedChange,
+    Exit,
+}
+
+/// Row 0 is reserved for controls; everything above it mirrors the board.
+const CONTROL_ROW: u8 = 0;
+const BOARD_MIRROR_ROWS: u8 = 8;
+const BOARD_MIRROR_COLS: u8 = 8;
+
+fn control_event_for_pad(pad: Pad) -> Option<ControlEvent> {
+    if pad.y != CONTROL_ROW {
+        return None;
+    }
+    match pad.x {
+        0 => Some(ControlEvent::MoveLeft),
+        1 => Some(ControlEvent::MoveRight),
+        2 => Some(ControlEvent::Rotate),
+        3 => Some(ControlEvent::SoftDrop),
+        4 => Some(ControlEvent::HardDrop),
+        5 => Some(ControlEvent::SpeedChange),
+        6 => Some(ControlEvent::Exit),
+        _ => None,
+    }
+}
+
+/// Velocity bytes, Launchpad MK1-style (low nibble green, high nibble red).
+const PAD_OFF: u8 = 0x0C;
+const PAD_ACTIVE_PIECE: u8 = 0x3F;
+const PAD_SETTLED_BLOCK: u8 = 0x3C;
+const PAD_FLOOR: u8 = 0x0F;
+
+/// Holds the open MIDI connections for the lifetime of the app. The input
+/// connection's callback runs on its own thread and forwards decoded
+/// `ControlEvent`s back to the main thread through `events`.
+pub struct MidiGrid {
+    _input: MidiInputConnection<()>,
+    output: MidiOutputConnection,
+    events: Receiver<(ControlEvent, bool)>,
+}
+
+/// Maps a `ControlEvent` to the `KeyCode` it shadows, so `midi_input` can
+/// press and release the same key a note-on/note-off pair decoded into.
+/// `Exit` has no key of its own; it fires `AppExit` directly on note-on.
+fn key_code_for(event: ControlEvent) -> Option<KeyCode> {
+    match event {
+        ControlEvent::MoveLeft => Some(KeyCode::Left),
+        ControlEvent::MoveRight => Some(KeyCode::Right),
+        ControlEvent::Rotate => Some(KeyCode::Up),
+        ControlEvent::SoftDrop => Some(KeyCode::Down),
+        ControlEvent::HardDrop => Some(KeyCode::Space),
+        ControlEvent::SpeedChange => Some(KeyCode::LShift),
+        ControlEvent::Exit => None,
+    }
+}
+
+/// startup system (only added when the `midi` feature is enabled)
+pub fn setup_midi(commands: &mut Commands) {
+    let mut midi_in = MidiInput::new("floppy-brick input").expect("failed to open MIDI input");
+    midi_in.ignore(Ignore::None);
+    let in_port = match midi_in.ports().into_iter().next() {
+        Some(port) => port,
+        None => {
+            println!("No MIDI input device found, grid controller support disabled.");
+            return;
+        }
+    };
+
+    let midi_out = MidiOutput::new("floppy-brick output").expect("failed to open MIDI output");
+    let out_port = match midi_out.ports().into_iter().next() {
+        Some(port) => port,
+        None => {
+            println!("No MIDI output device found, grid controller support disabled.");
+            return;
+        }
+    };
+
+    let (sender, receiver) = channel();
+
+    let input_connection = midi_in
+        .connect(
+            &in_port,
+            "floppy-brick-input",
+            move |_timestamp, message, _| {
+                if message.len() < 3 {
+                    return;
+                }
+                // Note on: status 0x9?, velocity > 0. Note off: status 0x8?,
+                // or a 0x9? with velocity 0 (the conventional MIDI shorthand).
+                let status = message[0] & 0xF0;
+                let note_on = status == 0x90 && message[2] > 0;
+                let note_off = status == 0x80 || (status == 0x90 && message[2] == 0);
+                if !note_on && !note_off {
+                    return;
+                }
+                let pad = match Pad::from_note(message[1]) {
+                    Some(pad) => pad,
+                    None => return,
+                };
+                if let Some(event) = control_event_for_pad(pad) {
+                    let _ = sender.send((event, note_on));
+                }
+            },
+            (),
+        )
+        .expect("failed to connect to MIDI input port");
+
+    let output_connection = midi_out
+        .connect(&out_port, "floppy-brick-output")
+        .expect("failed to connect to MIDI output port");
+
+    commands.insert_resource(MidiGrid {
+        _input: input_connection,
+        output: output_connection,
+        events: receiver,
+    });
+}
+
+/// system (only added when the `midi` feature is enabled)
+///
+/// Drain decoded grid-controller events and funnel them into the same key
+/// state the keyboard systems read, so the rest of the game doesn't need to
+/// know the input came from a MIDI device.
+pub fn midi_input(
+    midi_grid: Option<ResMut<MidiGrid>>,
+    mut keyboard_input: ResMut<Input<KeyCode>>,
+    mut app_exit_events: ResMut<Events<AppExit>>,
+) {
+    let midi_grid = match midi_grid {
+        Some(midi_grid) => midi_grid,
+        None => return,
+    };
+
+    loop {
+        match midi_grid.events.try_recv() {
+            Ok((ControlEvent::Exit, true)) => app_exit_events.send(AppExit),
+            Ok((ControlEvent::Exit, false)) => {}
+            Ok((event, true)) => {
+                if let Some(key) = key_code_for(event) {
+                    keyboard_input.press(key);
+                }
+            }
+            Ok((event, false)) => {
+                if let Some(key) = key_code_for(event) {
+                    keyboard_input.release(key);
+                }
+            }
+            Err(TryRecvError::Empty) | Err(TryRecvError::Disconnected) => break,
+        }
+    }
+}
+
+/// system (only added when the `midi` feature is enabled)
+///
+/// Mirror the board back out onto the grid: the floor along the bottom
+/// mirrored row, settled blocks, and the active tetromino, each in their own
+/// velocity-encoded color.
+pub fn midi_render(
+    midi_grid: Option<ResMut<MidiGrid>>,
+    game: Res<Game>,
+    block_query: Query<(Entity, &Block, &RigidBodyHandleComponent)>,
+    rigid_bodies: Res<RigidBodySet>,
+) {
+    let mut midi_grid = match midi_grid {
+        Some(midi_grid) => midi_grid,
+        None => return,
+    };
+
+    // Blank every pad first, so pads a block has moved away from go dark.
+    clear_grid(&mut midi_grid);
+
+    for x in 0..BOARD_MIRROR_COLS {
+        let pad = Pad {
+            x,
+            y: CONTROL_ROW + 1,
+        };
+        send_pad_color(&mut midi_grid, pad, PAD_FLOOR);
+    }
+
+    let mut lit: Vec<(IVector, u8)> = Vec::new();
+    for (entity, _, handle) in block_query.iter() {
+        let body = match rigid_bodies.get(handle.handle()) {
+            Some(body) => body,
+            None => continue,
+        };
+        let translation = body.position().translation;
+        let board_coord = game.physics_to_board((translation.x, translation.y));
+        let velocity = if game.current_tetromino_blocks.contains(&entity) {
+            PAD_ACTIVE_PIECE
+        } else {
+            PAD_SETTLED_BLOCK
+        };
+        lit.push((board_coord, velocity));
+    }
+
+    for ((col, row), velocity) in lit {
+        if col < 0 || row < 0 {
+            continue;
+        }
+        let mirror_row = row as u8 + CONTROL_ROW + 1;
+        if col as u8 >= BOARD_MIRROR_COLS || mirror_row >= BOARD_MIRROR_ROWS + CONTROL_ROW + 1 {
+            continue;
+        }
+        let pad = Pad {
+            x: col as u8,
+            y: mirror_row,
+        };
+        send_pad_color(&mut midi_grid, pad, velocity);
+    }
+}
+
+fn send_pad_color(midi_grid: &mut MidiGrid, pad: Pad, velocity: u8) {
+    let _ = midi_grid.output.send(&[0x90, pad.to_note(), velocity]);
+}
+
+fn clear_grid(midi_grid: &mut MidiGrid) {
+    for y in 0..BOARD_MIRROR_ROWS + CONTROL_ROW + 1 {
+        for x in 0..BOARD_MIRROR_COLS {
+            send_pad_color(midi_grid, Pad { x, y }, PAD_OFF);
+        }
+    }
+}
@@ -0,0 +1,567 @@
+//! Deterministic discrete board simulation backing the (work in progress)
+//! two-player versus mode.
+//!
+//! Rapier's continuous physics is not bit-deterministic across machines, so
+//! it can only ever be a cosmetic local effect. Anything that has to agree
+//! between two peers under rollback netcode lives here instead, as plain
+//! data advanced by a single pure function, [`advance_frame`], keyed off a
+//! seeded RNG rather than `rand::thread_rng()` so the same input stream
+//! always reproduces the same states.
+//!
+//! [`VersusMatch`] is the GGRS-backed two-player mode itself: it drives one
+//! `BoardState` per player through a GGRS session, calling `advance_frame`
+//! for every frame the session asks it to simulate (resimulated frames
+//! included) and ferrying garbage lines between the two
+//! boards. It currently runs both boards locally under a GGRS synctest
+//! session rather than a real P2P one — that exercises the exact same
+//! rollback/save/load path a networked session would, which is what
+//! guarantees the simulation can't silently desync, but actually opening a
+//! socket to a remote peer (and transporting `PlayerInput` over the wire
+//! instead of reading it locally for both players) is left for a follow-up
+//! change.
+
+use ggrs::{Config, GgrsRequest, PlayerHandle, SessionBuilder, SyncTestSession};
+
+use crate::tetromino::{IVector, TetrominoKind};
+
+pub const BOARD_LANES: usize = 10;
+pub const BOARD_ROWS: usize = 20;
+
+const LINE_CLEAR_SCORES: [u32; 4] = [100, 300, 500, 800];
+const LINES_PER_LEVEL: u32 = 10;
+const BASE_GRAVITY_INTERVAL_TICKS: u64 = 48;
+const GRAVITY_INTERVAL_STEP_TICKS: u64 = 4;
+const MIN_GRAVITY_INTERVAL_TICKS: u64 = 4;
+
+/// One frame's worth of input, as a small POD bitflag struct so it is cheap
+/// to serialize and send over the wire every tick. `Pod`/`Zeroable` are what
+/// GGRS requires of `Config::Input`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, bytemuck::Pod, bytemuck::Zeroable)]
+#[repr(C)]
+pub struct PlayerInput(u8);
+
+impl PlayerInput {
+    pub const LEFT: u8 = 1 << 0;
+    pub const RIGHT: u8 = 1 << 1;
+    pub const ROTATE: u8 = 1 << 2;
+    pub const SOFT_DROP: u8 = 1 << 3;
+    pub const HARD_DROP: u8 = 1 << 4;
+
+    pub fn from_bits(bits: u8) -> Self {
+        PlayerInput(bits)
+    }
+
+    pub fn pressed(self, flag: u8) -> bool {
+        self.0 & flag != 0
+    }
+}
+
+/// A settled cell just needs to know which tetromino it came from, for
+/// rendering and for the checksum; the discrete model has no physics to
+/// simulate.
+type Cell = Option<TetrominoKind>;
+
+/// The full state that must stay identical between both peers. Everything
+/// a rollback needs to restore lives here, nothing more.
+#[derive(Clone)]
+pub struct BoardState {
+    cells: [[Cell; BOARD_LANES]; BOARD_ROWS],
+    active_kind: TetrominoKind,
+    active_coords: [IVector; 4],
+    /// Board coordinates of the active tetromino's local (0, 0).
+    active_origin: IVector,
+    /// SRS rotation state (0 = spawn, 1 = R, 2 = 2, 3 = L), used to look up
+    /// the right wall-kick table on the next clockwise rotation.
+    active_rotation: u8,
+    next_kind: TetrominoKind,
+    rng_state: u64,
+    pub tick: u64,
+    next_gravity_tick: u64,
+    pub score: u32,
+    pub level: u32,
+    pub lines_cleared: u32,
+    /// Garbage lines earned by this board's own clears, waiting to be
+    /// delivered to the opponent's board by `VersusMatch::advance`.
+    pub garbage_to_send: u32,
+}
+
+impl BoardState {
+    /// `seed` must be non-zero; it is the only source of randomness, so the
+    /// same seed and input stream always produces the same game.
+    pub fn new(seed: u64) -> Self {
+        let mut rng_state = seed.max(1);
+        let active_kind = next_tetromino_kind(&mut rng_state);
+        let next_kind = next_tetromino_kind(&mut rng_state);
+
+        BoardState {
+            cells: [[None; BOARD_LANES]; BOARD_ROWS],
+            active_kind,
+            active_coords: active_kind.layout().coords,
+            active_origin: spawn_origin(),
+            active_rotation: 0,
+            next_kind,
+            rng_state,
+            tick: 0,
+            next_gravity_tick: gravity_interval_ticks(1),
+            score: 0,
+            level: 1,
+            lines_cleared: 0,
+            garbage_to_send: 0,
+        }
+    }
+}
+
+fn spawn_origin() -> IVector {
+    (BOARD_LANES as i32 / 2, BOARD_ROWS as i32 - 1)
+}
+
+/// A tiny xorshift64* generator. It only needs to be deterministic and
+/// cheap, not cryptographically sound.
+fn xorshift64(state: &mut u64) -> u64 {
+    let mut x = *state;
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    *state = x;
+    x
+}
+
+fn next_tetromino_kind(rng_state: &mut u64) -> TetrominoKind {
+    match xorshift64(rng_state) % 7 {
+        0 => TetrominoKind::I,
+        1 => TetrominoKind::O,
+        2 => TetrominoKind::T,
+        3 => TetrominoKind::J,
+        4 => TetrominoKind::L,
+        5 => TetrominoKind::S,
+        _ => TetrominoKind::Z,
+    }
+}
+
+fn gravity_interval_ticks(level: u32) -> u64 {
+    let steps = level.saturating_sub(1) as u64;
+    BASE_GRAVITY_INTERVAL_TICKS
+        .saturating_sub(steps * GRAVITY_INTERVAL_STEP_TICKS)
+        .max(MIN_GRAVITY_INTERVAL_TICKS)
+}
+
+fn occupied(cells: &[[Cell; BOARD_LANES]; BOARD_ROWS], (col, row): IVector) -> bool {
+    if col < 0 || col as usize >= BOARD_LANES || row < 0 {
+        return true;
+    }
+    match cells.get(row as usize) {
+        Some(lane) => lane[col as usize].is_some(),
+        None => false,
+    }
+}
+
+fn fits(cells: &[[Cell; BOARD_LANES]; BOARD_ROWS], coords: &[IVector; 4], origin: IVector) -> bool {
+    coords
+        .iter()
+        .all(|&(x, y)| !occupied(cells, (origin.0 + x, origin.1 + y)))
+}
+
+/// Rotate 90 degrees clockwise about the pivot block, `coords[1]`.
+fn rotate_clockwise(coords: &[IVector; 4]) -> [IVector; 4] {
+    let (pivot_x, pivot_y) = coords[1];
+    let mut rotated = [(0, 0); 4];
+    for (i, &(x, y)) in coords.iter().enumerate() {
+        rotated[i] = (pivot_x + (y - pivot_y), pivot_y - (x - pivot_x));
+    }
+    rotated
+}
+
+/// The single place game-affecting state changes. Pure aside from the RNG
+/// state carried inside `state`: the same `state` and `input` always
+/// produce the same result, which is what makes rollback possible.
+pub fn advance_frame(state: &BoardState, input: PlayerInput) -> BoardState {
+    let mut next = state.clone();
+    next.tick += 1;
+
+    if input.pressed(PlayerInput::LEFT) {
+        try_shift(&mut next, (-1, 0));
+    }
+    if input.pressed(PlayerInput::RIGHT) {
+        try_shift(&mut next, (1, 0));
+    }
+    if input.pressed(PlayerInput::ROTATE) {
+        try_rotate(&mut next);
+    }
+
+    if input.pressed(PlayerInput::HARD_DROP) {
+        while try_shift(&mut next, (0, -1)) {
+            next.score += 2;
+        }
+        lock_active(&mut next);
+        return next;
+    }
+
+    let gravity_due = next.tick >= next.next_gravity_tick;
+    if input.pressed(PlayerInput::SOFT_DROP) || gravity_due {
+        if try_shift(&mut next, (0, -1)) {
+            if input.pressed(PlayerInput::SOFT_DROP) {
+                next.score += 1;
+            }
+        } else {
+            lock_active(&mut next);
+        }
+    }
+    if gravity_due {
+        next.next_gravity_tick = next.tick + gravity_interval_ticks(next.level);
+    }
+
+    next
+}
+
+fn try_shift(state: &mut BoardState, (dx, dy): IVector) -> bool {
+    let candidate_origin = (state.active_origin.0 + dx, state.active_origin.1 + dy);
+    if !fits(&state.cells, &state.active_coords, candidate_origin) {
+        return false;
+    }
+    state.active_origin = candidate_origin;
+    true
+}
+
+/// SRS wall-kick offsets to try, in order, for a clockwise rotation starting
+/// from `from_rotation` (0 = spawn, 1 = R, 2 = 2, 3 = L). Kept in lockstep
+/// with the identical table in `main.rs`'s `tetromino_rotation`, so rotations
+/// near walls/stacks behave the same in both simulations.
+fn wall_kicks(kind: TetrominoKind, from_rotation: u8) -> [IVector; 5] {
+    const JLSTZ_KICKS: [[IVector; 5]; 4] = [
+        [(0, 0), (-1, 0), (-1, 1), (0, -2), (-1, -2)],
+        [(0, 0), (1, 0), (1, -1), (0, 2), (1, 2)],
+        [(0, 0), (1, 0), (1, 1), (0, -2), (1, -2)],
+        [(0, 0), (-1, 0), (-1, -1), (0, 2), (-1, 2)],
+    ];
+    const I_KICKS: [[IVector; 5]; 4] = [
+        [(0, 0), (-2, 0), (1, 0), (-2, -1), (1, 2)],
+        [(0, 0), (-1, 0), (2, 0), (-1, 2), (2, -1)],
+        [(0, 0), (2, 0), (-1, 0), (2, 1), (-1, -2)],
+        [(0, 0), (1, 0), (-2, 0), (1, -2), (-2, 1)],
+    ];
+    const NO_KICKS: [IVector; 5] = [(0, 0), (0, 0), (0, 0), (0, 0), (0, 0)];
+
+    let table = match kind {
+        TetrominoKind::O => return NO_KICKS,
+        TetrominoKind::I => I_KICKS,
+        _ => JLSTZ_KICKS,
+    };
+
+    table[from_rotation as usize]
+}
+
+fn try_rotate(state: &mut BoardState) -> bool {
+    // The O piece has no block at its centre, so rotating it about its
+    // pivot block in `rotate_clockwise` would visibly translate it despite
+    // `wall_kicks` returning an all-zero table for it. An O never needs to
+    // rotate in the first place, so just skip it entirely.
+    if let TetrominoKind::O = state.active_kind {
+        return true;
+    }
+
+    let rotated = rotate_clockwise(&state.active_coords);
+
+    for &(dx, dy) in wall_kicks(state.active_kind, state.active_rotation).iter() {
+        let candidate_origin = (state.active_origin.0 + dx, state.active_origin.1 + dy);
+        if fits(&state.cells, &rotated, candidate_origin) {
+            state.active_coords = rotated;
+            state.active_origin = candidate_origin;
+            state.active_rotation = (state.active_rotation + 1) % 4;
+            return true;
+        }
+    }
+
+    false
+}
+
+fn lock_active(state: &mut BoardState) {
+    for &(x, y) in &state.active_coords {
+        let (col, row) = (state.active_origin.0 + x, state.active_origin.1 + y);
+        if row >= 0 && (row as usize) < BOARD_ROWS && col >= 0 && (col as usize) < BOARD_LANES {
+            state.cells[row as usize][col as usize] = Some(state.active_kind);
+        }
+    }
+
+    let lines_cleared_now = clear_completed_rows(state);
+    if lines_cleared_now > 0 {
+        let index = (lines_cleared_now as usize).min(LINE_CLEAR_SCORES.len()) - 1;
+        state.score += LINE_CLEAR_SCORES[index] * state.level;
+        state.lines_cleared += lines_cleared_now;
+        state.level = 1 + state.lines_cleared / LINES_PER_LEVEL;
+        if lines_cleared_now >= 2 {
+            state.garbage_to_send += lines_cleared_now - 1;
+        }
+    }
+
+    state.active_kind = state.next_kind;
+    state.active_coords = state.active_kind.layout().coords;
+    state.active_origin = spawn_origin();
+    state.active_rotation = 0;
+    state.next_kind = next_tetromino_kind(&mut state.rng_state);
+}
+
+/// Unlike the physics view, clearing here can just shift rows down directly.
+fn clear_completed_rows(state: &mut BoardState) -> u32 {
+    let mut remaining: Vec<[Cell; BOARD_LANES]> = state
+        .cells
+        .iter()
+        .copied()
+        .filter(|row| row.iter().any(Option::is_none))
+        .collect();
+
+    let cleared = BOARD_ROWS - remaining.len();
+    while remaining.len() < BOARD_ROWS {
+        remaining.push([None; BOARD_LANES]);
+    }
+    for (row, cells) in state.cells.iter_mut().zip(remaining.into_iter()) {
+        *row = cells;
+    }
+
+    cleared as u32
+}
+
+/// Insert garbage rows (sent by the other player's `garbage_to_send`) at the
+/// bottom of this board, pushing everything else up.
+pub fn receive_garbage(state: &mut BoardState, lines: u32, hole_lane: usize) {
+    for _ in 0..lines {
+        let mut garbage_row = [Some(TetrominoKind::I); BOARD_LANES];
+        if hole_lane < BOARD_LANES {
+            garbage_row[hole_lane] = None;
+        }
+        state.cells.rotate_right(1);
+        state.cells[0] = garbage_row;
+    }
+}
+
+/// A checksum over everything that must agree between peers, so a rollback
+/// session can tell the moment two simulations have desynced.
+pub fn checksum(state: &BoardState) -> u64 {
+    let hash = std::cell::Cell::new(0xcbf2_9ce4_8422_2325u64);
+    let mix_byte = |byte: u8| {
+        hash.set((hash.get() ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3));
+    };
+    let mix_u64 = |value: u64| {
+        for byte in value.to_le_bytes() {
+            mix_byte(byte);
+        }
+    };
+
+    for lane in state.cells.iter() {
+        for cell in lane.iter() {
+            mix_byte(cell.map(|kind| kind as u8 + 1).unwrap_or(0));
+        }
+    }
+    mix_byte(state.active_kind as u8);
+    for &(x, y) in &state.active_coords {
+        mix_byte(x as u8);
+        mix_byte(y as u8);
+    }
+    mix_u64(state.active_origin.0 as u64);
+    mix_u64(state.active_origin.1 as u64);
+    mix_byte(state.active_rotation);
+    mix_byte(state.next_kind as u8);
+    mix_u64(state.tick);
+    mix_u64(state.score as u64);
+    mix_u64(state.level as u64);
+    mix_u64(state.lines_cleared as u64);
+    mix_u64(state.rng_state);
+    mix_u64(state.next_gravity_tick);
+    mix_u64(state.garbage_to_send as u64);
+
+    hash.get()
+}
+
+/// A saved snapshot plus the checksum it was saved with, so `load_state`
+/// can assert nothing was corrupted in between.
+pub struct SavedState {
+    state: BoardState,
+    checksum: u64,
+}
+
+pub fn save_state(state: &BoardState) -> SavedState {
+    SavedState {
+        state: state.clone(),
+        checksum: checksum(state),
+    }
+}
+
+pub fn load_state(saved: &SavedState) -> BoardState {
+    debug_assert_eq!(
+        checksum(&saved.state),
+        saved.checksum,
+        "board state checksum mismatch on load"
+    );
+    saved.state.clone()
+}
+
+/// Ties `BoardState`/`PlayerInput` to GGRS's session types.
+pub struct NetcodeConfig;
+
+impl Config for NetcodeConfig {
+    type Input = PlayerInput;
+    type State = MatchState;
+    type Address = String;
+}
+
+/// The combined state of both players' boards, saved and loaded as a single
+/// unit by GGRS's rollback.
+#[derive(Clone)]
+pub struct MatchState {
+    boards: [BoardState; 2],
+}
+
+/// A two-player versus match, advanced one confirmed frame at a time by a
+/// GGRS session. See the module docs for what's real here versus what's
+/// still a local stand-in for a networked session.
+pub struct VersusMatch {
+    session: SyncTestSession<NetcodeConfig>,
+    boards: [BoardState; 2],
+}
+
+impl VersusMatch {
+    /// `seed` must be non-zero; both boards start from it so a replay of the
+    /// same input stream reproduces the same match exactly like it does for
+    /// a single `BoardState`.
+    pub fn new(seed: u64, check_distance: usize) -> Self {
+        let session = SessionBuilder::<NetcodeConfig>::new()
+            .with_num_players(2)
+            .with_check_distance(check_distance)
+            .start_synctest_session()
+            .expect("failed to start local synctest session");
+
+        VersusMatch {
+            session,
+            boards: [
+                BoardState::new(seed),
+                BoardState::new(seed.wrapping_add(1).max(1)),
+            ],
+        }
+    }
+
+    pub fn board(&self, player: usize) -> &BoardState {
+        &self.boards[player]
+    }
+
+    /// Advance both boards by one frame given each player's local input,
+    /// delivering any garbage lines a clear earned this frame to the other
+    /// player's board.
+    pub fn advance(&mut self, inputs: [PlayerInput; 2]) {
+        for (handle, input) in inputs.iter().enumerate() {
+            self.session
+                .add_local_input(handle as PlayerHandle, *input)
+                .expect("failed to add local input");
+        }
+
+        let requests = match self.session.advance_frame() {
+            Ok(requests) => requests,
+            Err(_) => return,
+        };
+
+        for request in requests {
+            match request {
+                GgrsRequest::SaveGameState { cell, frame } => {
+                    let state = MatchState {
+                        boards: self.boards.clone(),
+                    };
+                    let combined_checksum =
+                        checksum(&state.boards[0]) ^ checksum(&state.boards[1]).rotate_left(32);
+                    cell.save(frame, Some(state), Some(combined_checksum));
+                }
+                GgrsRequest::LoadGameState { cell, .. } => {
+                    self.boards = cell.load().boards;
+                }
+                GgrsRequest::AdvanceFrame { inputs } => {
+                    for (handle, board) in self.boards.iter_mut().enumerate() {
+                        let (input, _status) = inputs[handle];
+                        *board = advance_frame(board, input);
+                    }
+
+                    let outgoing = [
+                        self.boards[0].garbage_to_send,
+                        self.boards[1].garbage_to_send,
+                    ];
+                    self.boards[0].garbage_to_send = 0;
+                    self.boards[1].garbage_to_send = 0;
+                    if outgoing[1] > 0 {
+                        receive_garbage(&mut self.boards[0], outgoing[1], 0);
+                    }
+                    if outgoing[0] > 0 {
+                        receive_garbage(&mut self.boards[1], outgoing[0], 0);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The whole point of this module: the same seed and input stream must
+    /// always produce the same state, or rollback can't work.
+    #[test]
+    fn advance_frame_is_deterministic_for_the_same_seed_and_inputs() {
+        let inputs = [
+            PlayerInput::from_bits(PlayerInput::LEFT),
+            PlayerInput::from_bits(PlayerInput::ROTATE),
+            PlayerInput::from_bits(PlayerInput::SOFT_DROP),
+            PlayerInput::from_bits(0),
+        ];
+
+        let run = |seed: u64| {
+            let mut state = BoardState::new(seed);
+            for &input in &inputs {
+                state = advance_frame(&state, input);
+            }
+            checksum(&state)
+        };
+
+        assert_eq!(run(42), run(42));
+    }
+
+    #[test]
+    fn save_and_load_state_round_trips_the_checksum() {
+        let mut state = BoardState::new(7);
+        state = advance_frame(&state, PlayerInput::from_bits(PlayerInput::LEFT));
+
+        let saved = save_state(&state);
+        let loaded = load_state(&saved);
+
+        assert_eq!(checksum(&loaded), checksum(&state));
+    }
+
+    #[test]
+    fn wall_kicks_first_candidate_is_always_the_identity() {
+        for kind in [TetrominoKind::T, TetrominoKind::I, TetrominoKind::J] {
+            for rotation in 0..4u8 {
+                assert_eq!(wall_kicks(kind, rotation)[0], (0, 0));
+            }
+        }
+    }
+
+    #[test]
+    fn jlstz_kicks_keep_a_piece_against_the_left_wall_in_bounds() {
+        let origin: IVector = (0, 5);
+        let rotated = rotate_clockwise(&TetrominoKind::J.layout().coords);
+
+        let has_in_bounds_kick = wall_kicks(TetrominoKind::J, 0).iter().any(|(dx, dy)| {
+            rotated
+                .iter()
+                .all(|(x, y)| origin.0 + x + dx >= 0 && (origin.0 + x + dx) < BOARD_LANES as i32)
+        });
+
+        assert!(has_in_bounds_kick);
+    }
+
+    #[test]
+    fn try_rotate_is_a_no_op_for_the_o_piece() {
+        let mut state = BoardState::new(1);
+        state.active_kind = TetrominoKind::O;
+        state.active_coords = TetrominoKind::O.layout().coords;
+        let before = state.active_coords;
+
+        assert!(try_rotate(&mut state));
+        assert_eq!(state.active_coords, before);
+    }
+}